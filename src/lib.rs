@@ -1,95 +1,257 @@
+// 本模块依赖的外部 crate 均假定已经在工作区 `Cargo.toml` 中声明：
+// `tokio`（features: "net", "rt-multi-thread", "sync", "time", "io-util", "macros"）、
+// `tokio-tungstenite`、`tokio-rustls`、`rustls-pemfile`、`futures-util`、
+// `serde` / `serde_json`（derive）、`uuid`（v4）。这个源码树本身不带 Cargo.toml，
+// 因此这里仅作为一份清单留档，实际的依赖声明需要在工作区清单里确认/补齐。
 use futures_util::{SinkExt, StreamExt};
 use plugin_interfaces::{
     create_plugin_interface_from_handler, log_info, log_warn,
     pluginui::{Context, Ui},
     PluginHandler, PluginInstanceContext, PluginInterface,
 };
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::{runtime::Runtime, sync::Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::task::JoinSet;
+use tokio::{runtime::Runtime, sync::mpsc, sync::watch, sync::Mutex};
 use tokio_tungstenite::{accept_async, tungstenite::Message};
 use uuid::Uuid;
 
+mod protocol;
+use protocol::{ClientFrame, Destination, InboundMessage, OutboundMessage};
+
+/// 房间名到成员 client id 集合的映射
+type Rooms = Arc<Mutex<HashMap<String, HashSet<String>>>>;
+
+/// 监听地址的抽象：同一套 `handle_client` 既可以跑在 TCP 连接上，也可以跑在
+/// Unix Domain Socket 上，这里只负责描述"绑定在哪"。
+#[derive(Debug, Clone)]
+pub enum BindAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
 /// WebSocket 客户端信息
 #[derive(Debug, Clone)]
 pub struct ClientInfo {
     pub id: String,
-    pub addr: SocketAddr,
-    pub sender: Arc<
-        Mutex<
-            futures_util::stream::SplitSink<
-                tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
-                Message,
-            >,
-        >,
-    >,
+    /// 对端地址的可读表示；TCP 下是 `ip:port`，Unix Socket 下是对端描述符信息。
+    pub addr: String,
+    /// 指向该客户端专属写任务的发送端；实际的 `SplitSink` 由写任务独占持有，
+    /// 这里只保留一个无锁的 `Sender`，避免广播时相互阻塞。
+    pub sender: mpsc::UnboundedSender<Message>,
 }
 
 /// WebSocket 服务器插件实现
 #[derive(Clone)]
 pub struct WebSocketServerPlugin {
-    server_running: Arc<Mutex<bool>>,
+    /// 关闭信号：`true` 表示服务器应当停止。接受循环和每个连接都持有一份
+    /// `watch::Receiver`，一旦收到变更就会尽快退出，取代原先的轮询标志。
+    shutdown_tx: Arc<Mutex<Option<watch::Sender<bool>>>>,
     server_address: String,
     server_port: String,
     clients: Arc<Mutex<HashMap<String, ClientInfo>>>,
+    /// 房间名到成员 client id 集合的映射，用于群组广播
+    rooms: Rooms,
+    /// 所有已派生的连接任务，停止服务器时据此等待它们全部结束，而不是 sleep。
+    connections: Arc<Mutex<JoinSet<()>>>,
     selected_client: Option<String>,
     runtime: Option<Arc<Runtime>>,
     server_handle: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// 心跳 Ping 的发送周期（秒）
+    heartbeat_interval_secs: String,
+    /// 超过多久收不到 Pong 就判定连接已死（秒），默认是两个心跳周期
+    heartbeat_timeout_secs: String,
+    /// 传输模式："TCP" / "TCP + TLS (wss)" / "Unix Socket"，对应下拉框选择
+    transport_mode: Option<String>,
+    /// Unix Domain Socket 模式下监听的路径
+    unix_socket_path: String,
+    /// TLS 证书文件路径（PEM）
+    tls_cert_path: String,
+    /// TLS 私钥文件路径（PEM）
+    tls_key_path: String,
+    /// 是否启用结构化 JSON 协议；关闭时保持原始文本兼容模式
+    structured_protocol_enabled: bool,
 }
 
 impl WebSocketServerPlugin {
+    const TRANSPORT_TCP: &'static str = "TCP";
+    const TRANSPORT_TCP_TLS: &'static str = "TCP + TLS (wss)";
+    const TRANSPORT_UNIX: &'static str = "Unix Socket";
+    /// 目标选择下拉框里，房间名前面加的前缀，用来和 client id 区分开
+    const ROOM_TARGET_PREFIX: &'static str = "房间:";
+    /// 目标客户端选择框中代表"发给所有客户端"的选项标签。
+    const BROADCAST_TARGET_LABEL: &'static str = "全局广播";
+
     fn new() -> Self {
         Self {
-            server_running: Arc::new(Mutex::new(false)),
+            shutdown_tx: Arc::new(Mutex::new(None)),
             server_address: "127.0.0.1".to_string(),
             server_port: "8080".to_string(),
             clients: Arc::new(Mutex::new(HashMap::new())),
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+            connections: Arc::new(Mutex::new(JoinSet::new())),
             selected_client: None,
             runtime: None,
             server_handle: Arc::new(Mutex::new(None)),
+            heartbeat_interval_secs: "30".to_string(),
+            heartbeat_timeout_secs: "60".to_string(),
+            transport_mode: Some(Self::TRANSPORT_TCP.to_string()),
+            unix_socket_path: "/tmp/websocket-server-plugin.sock".to_string(),
+            tls_cert_path: String::new(),
+            tls_key_path: String::new(),
+            structured_protocol_enabled: false,
+        }
+    }
+
+    /// 解析心跳间隔，解析失败时回退到 30 秒默认值
+    fn heartbeat_interval(&self) -> std::time::Duration {
+        let secs = self.heartbeat_interval_secs.parse::<u64>().unwrap_or(30);
+        std::time::Duration::from_secs(secs.max(1))
+    }
+
+    /// 解析心跳超时，解析失败时回退到 60 秒默认值（两个心跳周期）
+    fn heartbeat_timeout(&self) -> std::time::Duration {
+        let secs = self.heartbeat_timeout_secs.parse::<u64>().unwrap_or(60);
+        std::time::Duration::from_secs(secs.max(1))
+    }
+
+    /// 根据当前 UI 选择得到绑定地址
+    fn bind_addr(&self) -> BindAddr {
+        if self.transport_mode.as_deref() == Some(Self::TRANSPORT_UNIX) {
+            BindAddr::Unix(PathBuf::from(&self.unix_socket_path))
+        } else {
+            BindAddr::Tcp(format!("{}:{}", self.server_address, self.server_port))
         }
     }
 
-    /// 启动 WebSocket 服务器
+    fn tls_enabled(&self) -> bool {
+        self.transport_mode.as_deref() == Some(Self::TRANSPORT_TCP_TLS)
+    }
+
+    /// 从配置的证书/私钥路径构建一个 TLS acceptor
+    fn build_tls_acceptor(&self) -> Result<tokio_rustls::TlsAcceptor, String> {
+        let cert_file = std::fs::File::open(&self.tls_cert_path)
+            .map_err(|e| format!("无法打开证书文件 {}: {}", self.tls_cert_path, e))?;
+        let key_file = std::fs::File::open(&self.tls_key_path)
+            .map_err(|e| format!("无法打开私钥文件 {}: {}", self.tls_key_path, e))?;
+
+        let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(cert_file))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("解析证书失败: {}", e))?;
+
+        let key = rustls_pemfile::private_key(&mut std::io::BufReader::new(key_file))
+            .map_err(|e| format!("解析私钥失败: {}", e))?
+            .ok_or_else(|| "私钥文件中未找到私钥".to_string())?;
+
+        let config = tokio_rustls::rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("构建 TLS 配置失败: {}", e))?;
+
+        Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// 启动 WebSocket 服务器：根据绑定地址分派到 TCP 或 Unix Socket 的接受循环
     async fn start_server(&self, plugin_ctx: PluginInstanceContext) {
-        let addr = format!("{}:{}", self.server_address, self.server_port);
+        match self.bind_addr() {
+            BindAddr::Tcp(addr) => {
+                let tls_acceptor = if self.tls_enabled() {
+                    match self.build_tls_acceptor() {
+                        Ok(acceptor) => Some(acceptor),
+                        Err(e) => {
+                            log_warn!("Failed to build TLS acceptor: {}", e);
+                            plugin_ctx.send_message_to_frontend(&format!("启用 TLS 失败: {}", e));
+                            return;
+                        }
+                    }
+                } else {
+                    None
+                };
+                self.start_server_tcp(addr, tls_acceptor, plugin_ctx).await;
+            }
+            BindAddr::Unix(path) => {
+                self.start_server_unix(path, plugin_ctx).await;
+            }
+        }
+    }
+
+    /// TCP（可选 TLS）模式下的接受循环
+    async fn start_server_tcp(
+        &self,
+        addr: String,
+        tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+        plugin_ctx: PluginInstanceContext,
+    ) {
         log_info!("Starting WebSocket server on {}", addr);
 
         match tokio::net::TcpListener::bind(&addr).await {
             Ok(listener) => {
-                *self.server_running.lock().await = true;
+                let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+                *self.shutdown_tx.lock().await = Some(shutdown_tx);
+
                 plugin_ctx.send_message_to_frontend(&format!(
-                    "WebSocket 服务器已启动，监听地址: {}",
-                    addr
+                    "WebSocket 服务器已启动，监听地址: {}{}",
+                    addr,
+                    if tls_acceptor.is_some() { " (TLS)" } else { "" }
                 ));
                 plugin_ctx.refresh_ui();
 
-                let server_running = self.server_running.clone();
                 let plugin_ctx_clone = plugin_ctx.clone();
+                let heartbeat_interval = self.heartbeat_interval();
+                let heartbeat_timeout = self.heartbeat_timeout();
+                let structured_protocol_enabled = self.structured_protocol_enabled;
 
-                // 接受连接的主循环
+                // 接受连接的主循环：没有轮询 sleep，停止信号一到就立即退出。
                 loop {
-                    // 检查是否应该停止服务器
-                    if !*server_running.lock().await {
-                        log_info!("Server stop flag detected, breaking accept loop");
-                        break;
-                    }
-
-                    // 使用 select! 来同时监听连接和停止信号
                     tokio::select! {
                         result = listener.accept() => {
                             match result {
-                                Ok((stream, addr)) => {
-                                    log_info!("New connection from: {}", addr);
+                                Ok((stream, peer_addr)) => {
+                                    log_info!("New connection from: {}", peer_addr);
 
-                                    let clients_clone = self.clients.clone();
                                     let plugin_ctx_clone2 = plugin_ctx_clone.clone();
+                                    let conn_shutdown_rx = shutdown_rx.clone();
 
-                                    tokio::spawn(async move {
-                                        Self::handle_client(stream, addr, clients_clone, plugin_ctx_clone2)
-                                            .await;
-                                    });
+                                    if let Some(acceptor) = tls_acceptor.clone() {
+                                        let self_clone = self.clone();
+                                        tokio::spawn(async move {
+                                            match acceptor.accept(stream).await {
+                                                Ok(tls_stream) => {
+                                                    self_clone
+                                                        .spawn_connection(
+                                                            tls_stream,
+                                                            peer_addr.to_string(),
+                                                            plugin_ctx_clone2,
+                                                            heartbeat_interval,
+                                                            heartbeat_timeout,
+                                                            structured_protocol_enabled,
+                                                            conn_shutdown_rx,
+                                                        )
+                                                        .await;
+                                                }
+                                                Err(e) => {
+                                                    log_warn!(
+                                                        "TLS handshake failed for {}: {}",
+                                                        peer_addr,
+                                                        e
+                                                    );
+                                                }
+                                            }
+                                        });
+                                    } else {
+                                        self.spawn_connection(
+                                            stream,
+                                            peer_addr.to_string(),
+                                            plugin_ctx_clone2,
+                                            heartbeat_interval,
+                                            heartbeat_timeout,
+                                            structured_protocol_enabled,
+                                            conn_shutdown_rx,
+                                        )
+                                        .await;
+                                    }
                                 }
                                 Err(e) => {
                                     log_warn!("Failed to accept connection: {}", e);
@@ -98,9 +260,9 @@ impl WebSocketServerPlugin {
                                 }
                             }
                         }
-                        _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
-                            // 定期检查停止标志，确保能及时响应停止请求
-                            continue;
+                        _ = shutdown_rx.changed() => {
+                            log_info!("Shutdown signal received, breaking accept loop");
+                            break;
                         }
                     }
                 }
@@ -115,13 +277,131 @@ impl WebSocketServerPlugin {
         }
     }
 
-    /// 处理单个客户端连接
-    async fn handle_client(
-        stream: tokio::net::TcpStream,
-        addr: SocketAddr,
+    /// Unix Domain Socket 模式下的接受循环
+    async fn start_server_unix(&self, path: PathBuf, plugin_ctx: PluginInstanceContext) {
+        log_info!("Starting WebSocket server on unix://{}", path.display());
+
+        // 清理上一次非正常退出遗留的 socket 文件，否则 bind 会失败
+        if path.exists() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                log_warn!("Failed to remove stale socket file {}: {}", path.display(), e);
+            }
+        }
+
+        match tokio::net::UnixListener::bind(&path) {
+            Ok(listener) => {
+                let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+                *self.shutdown_tx.lock().await = Some(shutdown_tx);
+
+                plugin_ctx.send_message_to_frontend(&format!(
+                    "WebSocket 服务器已启动，监听地址: unix://{}",
+                    path.display()
+                ));
+                plugin_ctx.refresh_ui();
+
+                let plugin_ctx_clone = plugin_ctx.clone();
+                let heartbeat_interval = self.heartbeat_interval();
+                let heartbeat_timeout = self.heartbeat_timeout();
+                let structured_protocol_enabled = self.structured_protocol_enabled;
+
+                loop {
+                    tokio::select! {
+                        result = listener.accept() => {
+                            match result {
+                                Ok((stream, peer_addr)) => {
+                                    let peer_desc = format!("{:?}", peer_addr);
+                                    log_info!("New connection from: {}", peer_desc);
+
+                                    let plugin_ctx_clone2 = plugin_ctx_clone.clone();
+                                    let conn_shutdown_rx = shutdown_rx.clone();
+
+                                    self.spawn_connection(
+                                        stream,
+                                        peer_desc,
+                                        plugin_ctx_clone2,
+                                        heartbeat_interval,
+                                        heartbeat_timeout,
+                                        structured_protocol_enabled,
+                                        conn_shutdown_rx,
+                                    )
+                                    .await;
+                                }
+                                Err(e) => {
+                                    log_warn!("Failed to accept connection: {}", e);
+                                    break;
+                                }
+                            }
+                        }
+                        _ = shutdown_rx.changed() => {
+                            log_info!("Shutdown signal received, breaking accept loop");
+                            break;
+                        }
+                    }
+                }
+
+                log_info!("WebSocket server accept loop ended");
+                plugin_ctx_clone.send_message_to_frontend("WebSocket 服务器接受循环已结束");
+
+                // 正常退出时清理 socket 文件，避免下次启动 bind 失败
+                let _ = std::fs::remove_file(&path);
+            }
+            Err(e) => {
+                log_warn!("Failed to bind to unix://{}: {}", path.display(), e);
+                plugin_ctx.send_message_to_frontend(&format!("启动服务器失败: {}", e));
+            }
+        }
+    }
+
+    /// 把一个已接受的流（TCP / TLS / Unix Socket 均可）登记进 `connections`
+    /// 并交给 `handle_client` 处理，三种传输方式共用同一套连接生命周期管理。
+    async fn spawn_connection<S>(
+        &self,
+        stream: S,
+        addr: String,
+        plugin_ctx: PluginInstanceContext,
+        heartbeat_interval: std::time::Duration,
+        heartbeat_timeout: std::time::Duration,
+        structured_protocol_enabled: bool,
+        shutdown_rx: watch::Receiver<bool>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let clients_clone = self.clients.clone();
+        let rooms_clone = self.rooms.clone();
+        let mut connections = self.connections.lock().await;
+        // 顺手回收已经结束的连接任务，避免 JoinSet 在长期运行下无限增长；
+        // `stop_server` 中的清空逻辑只在关服时执行一次，不能替代这里的日常回收。
+        while connections.try_join_next().is_some() {}
+        connections.spawn(async move {
+            Self::handle_client(
+                stream,
+                addr,
+                clients_clone,
+                rooms_clone,
+                plugin_ctx,
+                heartbeat_interval,
+                heartbeat_timeout,
+                structured_protocol_enabled,
+                shutdown_rx,
+            )
+            .await;
+        });
+    }
+
+    /// 处理单个客户端连接；对传输层类型泛型，TCP、TLS、Unix Socket 走同一套逻辑
+    async fn handle_client<S>(
+        stream: S,
+        addr: String,
         clients: Arc<Mutex<HashMap<String, ClientInfo>>>,
+        rooms: Rooms,
         plugin_ctx: PluginInstanceContext,
-    ) {
+        heartbeat_interval: std::time::Duration,
+        heartbeat_timeout: std::time::Duration,
+        structured_protocol_enabled: bool,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         match accept_async(stream).await {
             Ok(ws_stream) => {
                 let client_id = Uuid::new_v4().to_string();
@@ -131,13 +411,18 @@ impl WebSocketServerPlugin {
                     addr
                 );
 
-                let (ws_sender, mut ws_receiver) = ws_stream.split();
+                let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+                // 每个客户端一个专属的无界 channel，读写分离：发送方只需要
+                // `tx.send(...)`，不再需要等待锁，慢客户端不会拖慢广播。
+                let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+                let heartbeat_tx = tx.clone();
 
                 // 创建客户端信息
                 let client_info = ClientInfo {
                     id: client_id.clone(),
-                    addr,
-                    sender: Arc::new(Mutex::new(ws_sender)),
+                    addr: addr.clone(),
+                    sender: tx,
                 };
 
                 // 添加到客户端列表
@@ -146,30 +431,128 @@ impl WebSocketServerPlugin {
                     .send_message_to_frontend(&format!("客户端已连接: {} ({})", client_id, addr));
                 plugin_ctx.refresh_ui();
 
+                // 独占 SplitSink 的写任务：串行消费 channel 中的消息并写入底层连接，
+                // 一旦写入失败或 channel 关闭，说明客户端已经不可达，清理客户端列表。
+                let writer_clients = clients.clone();
+                let writer_client_id = client_id.clone();
+                tokio::spawn(async move {
+                    while let Some(msg) = rx.recv().await {
+                        if let Err(e) = ws_sender.send(msg).await {
+                            log_warn!(
+                                "Failed to write to client {}: {}",
+                                writer_client_id,
+                                e
+                            );
+                            break;
+                        }
+                    }
+                    writer_clients.lock().await.remove(&writer_client_id);
+                });
+
+                // 心跳：周期性发送 Ping，并记录最近一次收到 Pong 的时间，
+                // 超过 `heartbeat_timeout`（约两个心跳周期）未收到 Pong 则视为连接已死。
+                let mut last_pong = tokio::time::Instant::now();
+                let mut heartbeat_ticker = tokio::time::interval(heartbeat_interval);
+                heartbeat_ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+                let mut timed_out = false;
+
                 // 处理消息接收
-                while let Some(msg) = ws_receiver.next().await {
-                    match msg {
-                        Ok(Message::Text(text)) => {
-                            log_info!("Received message from {}: {}", client_id, text);
-                            plugin_ctx
-                                .send_message_to_frontend(&format!("[{}] {}", client_id, text));
+                loop {
+                    tokio::select! {
+                        msg = ws_receiver.next() => {
+                            match msg {
+                                Some(Ok(Message::Text(text))) => {
+                                    log_info!("Received message from {}: {}", client_id, text);
+                                    if structured_protocol_enabled {
+                                        match serde_json::from_str::<ClientFrame>(&text) {
+                                            Ok(ClientFrame::Message(inbound)) => {
+                                                Self::route_inbound_message(
+                                                    &clients,
+                                                    &rooms,
+                                                    &client_id,
+                                                    inbound,
+                                                )
+                                                .await;
+                                            }
+                                            Ok(ClientFrame::JoinRoom { room }) => {
+                                                Self::join_room(&rooms, &room, &client_id).await;
+                                            }
+                                            Ok(ClientFrame::LeaveRoom { room }) => {
+                                                Self::leave_room(&rooms, &room, &client_id).await;
+                                            }
+                                            Err(e) => {
+                                                log_warn!(
+                                                    "Failed to parse structured message from {}: {}",
+                                                    client_id,
+                                                    e
+                                                );
+                                                plugin_ctx.send_message_to_frontend(&format!(
+                                                    "[{}] {}",
+                                                    client_id, text
+                                                ));
+                                            }
+                                        }
+                                    } else {
+                                        plugin_ctx.send_message_to_frontend(&format!(
+                                            "[{}] {}",
+                                            client_id, text
+                                        ));
+                                    }
+                                }
+                                Some(Ok(Message::Ping(payload))) => {
+                                    // tokio-tungstenite 默认会在读取到 Ping 时自动排队一个
+                                    // Pong，但那个 Pong 写在 `ws_sender`/`ws_receiver` 被
+                                    // split 前共享的内部缓冲区里，只有写任务主动 flush 时才会
+                                    // 真正发出去；这里的写任务只在 `heartbeat_tx` 收到新消息时
+                                    // 才会写流，所以必须显式把 Pong 送进同一个 channel，
+                                    // 否则客户端可能迟迟收不到回应。
+                                    let _ = heartbeat_tx.send(Message::Pong(payload));
+                                }
+                                Some(Ok(Message::Pong(_))) => {
+                                    last_pong = tokio::time::Instant::now();
+                                }
+                                Some(Ok(Message::Close(_))) => {
+                                    log_info!("Client {} disconnected", client_id);
+                                    break;
+                                }
+                                Some(Err(e)) => {
+                                    log_warn!("WebSocket error for client {}: {}", client_id, e);
+                                    break;
+                                }
+                                None => break,
+                                _ => {}
+                            }
                         }
-                        Ok(Message::Close(_)) => {
-                            log_info!("Client {} disconnected", client_id);
-                            break;
+                        _ = heartbeat_ticker.tick() => {
+                            if last_pong.elapsed() > heartbeat_timeout {
+                                log_warn!(
+                                    "Client {} missed heartbeat, evicting",
+                                    client_id
+                                );
+                                timed_out = true;
+                                break;
+                            }
+                            if heartbeat_tx.send(Message::Ping(Vec::new())).is_err() {
+                                break;
+                            }
                         }
-                        Err(e) => {
-                            log_warn!("WebSocket error for client {}: {}", client_id, e);
+                        _ = shutdown_rx.changed() => {
+                            log_info!("Shutting down connection to client {}", client_id);
+                            let _ = heartbeat_tx.send(Message::Close(None));
                             break;
                         }
-                        _ => {}
                     }
                 }
 
-                // 移除客户端
+                // 移除客户端，并退出它加入过的所有房间
                 clients.lock().await.remove(&client_id);
-                plugin_ctx
-                    .send_message_to_frontend(&format!("客户端已断开: {} ({})", client_id, addr));
+                Self::leave_all_rooms(&rooms, &client_id).await;
+                let disconnect_reason = if timed_out { "心跳超时" } else { "已断开" };
+                plugin_ctx.send_message_to_frontend(&format!(
+                    "客户端{}: {} ({})",
+                    disconnect_reason, client_id, addr
+                ));
                 plugin_ctx.refresh_ui();
             }
             Err(e) => {
@@ -179,42 +562,56 @@ impl WebSocketServerPlugin {
     }
 
     /// 停止 WebSocket 服务器
+    ///
+    /// 通过 `watch` channel 通知接受循环和所有连接任务关闭，然后真正 `await`
+    /// 它们结束（而不是 sleep 一段时间后硬 abort），保证关闭是确定性的、不丢连接。
     async fn stop_server(&self, plugin_ctx: &PluginInstanceContext) {
         log_info!("Stopping WebSocket server...");
 
-        // 1. 首先设置停止标志
-        *self.server_running.lock().await = false;
-
-        // 2. 取消服务器任务
-        if let Some(handle) = self.server_handle.lock().await.take() {
-            log_info!("Aborting server task...");
-            handle.abort();
-
-            // 等待任务完全结束
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        // 1. 广播停止信号：接受循环和每个连接的 select! 都在监听它
+        if let Some(shutdown_tx) = self.shutdown_tx.lock().await.take() {
+            let _ = shutdown_tx.send(true);
         }
 
-        // 3. 断开所有客户端连接
+        // 2. 向所有客户端发送关闭帧（连接任务收到 shutdown 信号后也会再发一次，无妨）
         {
-            let mut clients = self.clients.lock().await;
-            log_info!("Disconnecting {} clients...", clients.len());
-
-            // 向所有客户端发送关闭消息
+            let clients = self.clients.lock().await;
+            log_info!("Notifying {} clients of shutdown...", clients.len());
             for (client_id, client) in clients.iter() {
-                if let Ok(mut sender) = client.sender.try_lock() {
-                    let _ = sender.send(Message::Close(None)).await;
+                if client.sender.send(Message::Close(None)).is_ok() {
                     log_info!("Sent close message to client: {}", client_id);
                 }
             }
+        }
+
+        // 3. 等待接受循环任务结束
+        if let Some(handle) = self.server_handle.lock().await.take() {
+            if tokio::time::timeout(tokio::time::Duration::from_secs(5), handle)
+                .await
+                .is_err()
+            {
+                log_warn!("Timed out waiting for accept loop to end");
+            }
+        }
 
-            // 清空客户端列表
-            clients.clear();
+        // 4. 等待所有连接任务退出（把关闭帧放入写任务队列、清理自身在 clients 中的条目）；
+        //    真正写出关闭帧的写任务是单独 spawn 的，不在 `connections` 中，这里不等待它们。
+        {
+            let mut connections = self.connections.lock().await;
+            let drain = tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+                while connections.join_next().await.is_some() {}
+            })
+            .await;
+            if drain.is_err() {
+                log_warn!("Timed out waiting for connection tasks to finish, aborting the rest");
+                connections.shutdown().await;
+            }
         }
 
-        // 4. 等待一小段时间确保所有连接都已断开
-        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        // 5. 保险起见清空客户端列表（正常情况下每个连接任务退出时已自行移除）
+        self.clients.lock().await.clear();
 
-        // 5. 通知前端和刷新UI
+        // 6. 通知前端和刷新UI
         plugin_ctx.send_message_to_frontend("WebSocket 服务器已完全停止");
         plugin_ctx.refresh_ui();
 
@@ -225,8 +622,7 @@ impl WebSocketServerPlugin {
     async fn send_message_to_client(&self, client_id: &str, message: &str) -> Result<(), String> {
         let clients = self.clients.lock().await;
         if let Some(client) = clients.get(client_id) {
-            let mut sender = client.sender.lock().await;
-            match sender.send(Message::Text(message.to_string())).await {
+            match client.sender.send(Message::Text(message.to_string())) {
                 Ok(_) => {
                     log_info!("Message sent to client {}: {}", client_id, message);
                     Ok(())
@@ -247,8 +643,7 @@ impl WebSocketServerPlugin {
         let mut errors = Vec::new();
 
         for (client_id, client) in clients.iter() {
-            let mut sender = client.sender.lock().await;
-            if let Err(e) = sender.send(Message::Text(message.to_string())).await {
+            if let Err(e) = client.sender.send(Message::Text(message.to_string())) {
                 errors.push(format!("客户端 {}: {}", client_id, e));
             }
         }
@@ -265,6 +660,127 @@ impl WebSocketServerPlugin {
         }
     }
 
+    /// 按照结构化消息的 `destination` 字段，把入站消息直接路由给目标客户端，
+    /// 而不是像裸文本模式那样只转发给前端。
+    async fn route_inbound_message(
+        clients: &Arc<Mutex<HashMap<String, ClientInfo>>>,
+        rooms: &Rooms,
+        sender_id: &str,
+        inbound: InboundMessage,
+    ) {
+        let outbound = OutboundMessage {
+            from: Some(sender_id.to_string()),
+            payload: inbound.payload,
+        };
+        let text = match serde_json::to_string(&outbound) {
+            Ok(text) => text,
+            Err(e) => {
+                log_warn!("Failed to encode outbound message: {}", e);
+                return;
+            }
+        };
+
+        match inbound.destination {
+            Destination::Broadcast => {
+                let clients = clients.lock().await;
+                for (id, client) in clients.iter() {
+                    if id != sender_id {
+                        let _ = client.sender.send(Message::Text(text.clone()));
+                    }
+                }
+            }
+            Destination::Client(target_id) => {
+                let clients = clients.lock().await;
+                if let Some(client) = clients.get(&target_id) {
+                    let _ = client.sender.send(Message::Text(text));
+                } else {
+                    log_warn!("Routed message targets unknown client {}", target_id);
+                }
+            }
+            Destination::Room(room) => {
+                if let Err(e) =
+                    Self::broadcast_to_room_raw(clients, rooms, &room, &text, Some(sender_id))
+                        .await
+                {
+                    log_warn!("Failed to route message to room {}: {}", room, e);
+                }
+            }
+        }
+    }
+
+    /// 加入房间：把 client_id 加入 room 对应的成员集合
+    async fn join_room(rooms: &Rooms, room: &str, client_id: &str) {
+        rooms
+            .lock()
+            .await
+            .entry(room.to_string())
+            .or_default()
+            .insert(client_id.to_string());
+        log_info!("Client {} joined room {}", client_id, room);
+    }
+
+    /// 离开房间；房间变空时一并移除，避免 rooms 表无限增长
+    async fn leave_room(rooms: &Rooms, room: &str, client_id: &str) {
+        let mut rooms = rooms.lock().await;
+        if let Some(members) = rooms.get_mut(room) {
+            members.remove(client_id);
+            if members.is_empty() {
+                rooms.remove(room);
+            }
+        }
+        log_info!("Client {} left room {}", client_id, room);
+    }
+
+    /// 连接断开时，把该客户端从它所在的所有房间中移除
+    async fn leave_all_rooms(rooms: &Rooms, client_id: &str) {
+        let mut rooms = rooms.lock().await;
+        rooms.retain(|_, members| {
+            members.remove(client_id);
+            !members.is_empty()
+        });
+    }
+
+    /// 广播一段已经编码好的消息到房间内所有成员，可选排除发送者自己。
+    /// 复用每个客户端专属的 channel sender，某个成员的背压不会影响其它成员。
+    async fn broadcast_to_room_raw(
+        clients: &Arc<Mutex<HashMap<String, ClientInfo>>>,
+        rooms: &Rooms,
+        room: &str,
+        message: &str,
+        exclude: Option<&str>,
+    ) -> Result<(), String> {
+        let members = rooms
+            .lock()
+            .await
+            .get(room)
+            .cloned()
+            .ok_or_else(|| format!("房间不存在: {}", room))?;
+
+        let clients = clients.lock().await;
+        let mut errors = Vec::new();
+        for member_id in &members {
+            if Some(member_id.as_str()) == exclude {
+                continue;
+            }
+            if let Some(client) = clients.get(member_id) {
+                if let Err(e) = client.sender.send(Message::Text(message.to_string())) {
+                    errors.push(format!("客户端 {}: {}", member_id, e));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("部分发送失败: {}", errors.join(", ")))
+        }
+    }
+
+    /// 广播消息到指定房间内所有成员（供主机 API 调用，例如从 `handle_message` 下发）
+    async fn broadcast_to_room(&self, room: &str, message: &str) -> Result<(), String> {
+        Self::broadcast_to_room_raw(&self.clients, &self.rooms, room, message, None).await
+    }
+
     /// 启动服务器的异步任务
     fn start_server_task(&self, plugin_ctx: PluginInstanceContext) {
         if let Some(runtime) = &self.runtime {
@@ -286,17 +802,90 @@ impl PluginHandler for WebSocketServerPlugin {
 
         // 服务器控制区域
         ui.horizontal(|ui| {
-            ui.label("服务器地址:");
-            let text_response = ui.text_edit_singleline(&mut self.server_address);
-            if text_response.changed() {
-                log_info!("Server address changed to: {}", self.server_address);
+            ui.label("传输模式:");
+            let transport_options = vec![
+                Self::TRANSPORT_TCP.to_string(),
+                Self::TRANSPORT_TCP_TLS.to_string(),
+                Self::TRANSPORT_UNIX.to_string(),
+            ];
+            let transport_response =
+                ui.combo_box(transport_options, &mut self.transport_mode, "选择传输模式");
+            if transport_response.clicked() {
+                log_info!("Transport mode changed to: {:?}", self.transport_mode);
+            }
+        });
+
+        if self.transport_mode.as_deref() == Some(Self::TRANSPORT_UNIX) {
+            ui.horizontal(|ui| {
+                ui.label("Socket 路径:");
+                let path_response = ui.text_edit_singleline(&mut self.unix_socket_path);
+                if path_response.changed() {
+                    log_info!("Unix socket path changed to: {}", self.unix_socket_path);
+                }
+            });
+        } else {
+            ui.horizontal(|ui| {
+                ui.label("服务器地址:");
+                let text_response = ui.text_edit_singleline(&mut self.server_address);
+                if text_response.changed() {
+                    log_info!("Server address changed to: {}", self.server_address);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("服务器端口:");
+                let port_response = ui.text_edit_singleline(&mut self.server_port);
+                if port_response.changed() {
+                    log_info!("Server port changed to: {}", self.server_port);
+                }
+            });
+
+            if self.transport_mode.as_deref() == Some(Self::TRANSPORT_TCP_TLS) {
+                ui.horizontal(|ui| {
+                    ui.label("TLS 证书路径:");
+                    let cert_response = ui.text_edit_singleline(&mut self.tls_cert_path);
+                    if cert_response.changed() {
+                        log_info!("TLS cert path changed to: {}", self.tls_cert_path);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("TLS 私钥路径:");
+                    let key_response = ui.text_edit_singleline(&mut self.tls_key_path);
+                    if key_response.changed() {
+                        log_info!("TLS key path changed to: {}", self.tls_key_path);
+                    }
+                });
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("心跳间隔(秒):");
+            let interval_response = ui.text_edit_singleline(&mut self.heartbeat_interval_secs);
+            if interval_response.changed() {
+                log_info!(
+                    "Heartbeat interval changed to: {}",
+                    self.heartbeat_interval_secs
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("心跳超时(秒):");
+            let timeout_response = ui.text_edit_singleline(&mut self.heartbeat_timeout_secs);
+            if timeout_response.changed() {
+                log_info!(
+                    "Heartbeat timeout changed to: {}",
+                    self.heartbeat_timeout_secs
+                );
             }
         });
+
         ui.horizontal(|ui| {
-            ui.label("服务器端口:");
-            let port_response = ui.text_edit_singleline(&mut self.server_port);
-            if port_response.changed() {
-                log_info!("Server port changed to: {}", self.server_port);
+            let protocol_response =
+                ui.checkbox(&mut self.structured_protocol_enabled, "启用结构化协议 (JSON 信封)");
+            if protocol_response.changed() {
+                log_info!(
+                    "Structured protocol toggled to: {}",
+                    self.structured_protocol_enabled
+                );
             }
         });
 
@@ -306,13 +895,18 @@ impl PluginHandler for WebSocketServerPlugin {
         ui.horizontal(|ui| {
             ui.label("选择客户端:");
 
-            // 获取客户端列表
-            let mut client_options = vec!["全局广播".to_string()];
+            // 获取客户端列表，以及当前存在的房间列表
+            let mut client_options = vec![Self::BROADCAST_TARGET_LABEL.to_string()];
             if let Ok(clients) = self.clients.try_lock() {
                 for (client_id, _) in clients.iter() {
                     client_options.push(client_id.to_string());
                 }
             }
+            if let Ok(rooms) = self.rooms.try_lock() {
+                for room_name in rooms.keys() {
+                    client_options.push(format!("{}{}", Self::ROOM_TARGET_PREFIX, room_name));
+                }
+            }
 
             let combo_response =
                 ui.combo_box(client_options, &mut self.selected_client, "选择目标客户端");
@@ -439,16 +1033,36 @@ impl PluginHandler for WebSocketServerPlugin {
         if let Some(runtime) = &self.runtime {
             let self_clone = self.clone();
             let selected_client = self.selected_client.clone();
-            let message_owned = message.to_string();
             let plugin_ctx_clone = plugin_ctx.clone();
 
+            // 结构化协议开启时，主机发来的消息也封装成同样的 JSON 信封下发，
+            // 保持协议在两个方向上的一致性。
+            let outgoing = if self.structured_protocol_enabled {
+                let outbound = OutboundMessage {
+                    from: None,
+                    payload: serde_json::Value::String(message.to_string()),
+                };
+                serde_json::to_string(&outbound).unwrap_or_else(|e| {
+                    log_warn!("Failed to encode outbound message: {}", e);
+                    message.to_string()
+                })
+            } else {
+                message.to_string()
+            };
+
             runtime.spawn(async move {
-                let result = if let Some(client_id) = selected_client {
-                    self_clone
-                        .send_message_to_client(&client_id, &message_owned)
-                        .await
-                } else {
-                    self_clone.broadcast_message(&message_owned).await
+                let result = match selected_client {
+                    None => self_clone.broadcast_message(&outgoing).await,
+                    Some(ref target) if target == Self::BROADCAST_TARGET_LABEL => {
+                        self_clone.broadcast_message(&outgoing).await
+                    }
+                    Some(ref target) => {
+                        if let Some(room) = target.strip_prefix(Self::ROOM_TARGET_PREFIX) {
+                            self_clone.broadcast_to_room(room, &outgoing).await
+                        } else {
+                            self_clone.send_message_to_client(target, &outgoing).await
+                        }
+                    }
                 };
 
                 match result {
@@ -488,3 +1102,66 @@ pub unsafe extern "C" fn destroy_plugin(interface: *mut PluginInterface) {
         let _ = Box::from_raw(interface);
     }
 }
+
+#[cfg(test)]
+mod room_tests {
+    use super::*;
+
+    fn empty_rooms() -> Rooms {
+        Arc::new(Mutex::new(HashMap::new()))
+    }
+
+    #[tokio::test]
+    async fn join_room_creates_room_and_adds_member() {
+        let rooms = empty_rooms();
+        WebSocketServerPlugin::join_room(&rooms, "lobby", "alice").await;
+
+        let guard = rooms.lock().await;
+        assert_eq!(guard.get("lobby").unwrap(), &HashSet::from(["alice".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn join_room_twice_keeps_single_membership() {
+        let rooms = empty_rooms();
+        WebSocketServerPlugin::join_room(&rooms, "lobby", "alice").await;
+        WebSocketServerPlugin::join_room(&rooms, "lobby", "alice").await;
+
+        let guard = rooms.lock().await;
+        assert_eq!(guard.get("lobby").unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn leave_room_removes_member_but_keeps_room_if_not_empty() {
+        let rooms = empty_rooms();
+        WebSocketServerPlugin::join_room(&rooms, "lobby", "alice").await;
+        WebSocketServerPlugin::join_room(&rooms, "lobby", "bob").await;
+        WebSocketServerPlugin::leave_room(&rooms, "lobby", "alice").await;
+
+        let guard = rooms.lock().await;
+        assert_eq!(guard.get("lobby").unwrap(), &HashSet::from(["bob".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn leave_room_drops_room_entry_once_empty() {
+        let rooms = empty_rooms();
+        WebSocketServerPlugin::join_room(&rooms, "lobby", "alice").await;
+        WebSocketServerPlugin::leave_room(&rooms, "lobby", "alice").await;
+
+        let guard = rooms.lock().await;
+        assert!(!guard.contains_key("lobby"));
+    }
+
+    #[tokio::test]
+    async fn leave_all_rooms_removes_member_from_every_room() {
+        let rooms = empty_rooms();
+        WebSocketServerPlugin::join_room(&rooms, "lobby", "alice").await;
+        WebSocketServerPlugin::join_room(&rooms, "game", "alice").await;
+        WebSocketServerPlugin::join_room(&rooms, "game", "bob").await;
+
+        WebSocketServerPlugin::leave_all_rooms(&rooms, "alice").await;
+
+        let guard = rooms.lock().await;
+        assert!(!guard.contains_key("lobby"));
+        assert_eq!(guard.get("game").unwrap(), &HashSet::from(["bob".to_string()]));
+    }
+}
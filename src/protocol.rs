@@ -0,0 +1,96 @@
+//! 结构化消息协议：客户端选择以 JSON 信封通信时使用，替代裸文本广播/单发。
+
+use serde::{Deserialize, Serialize};
+
+/// 消息的投递目标
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Destination {
+    /// 广播给除发送者外的所有客户端
+    Broadcast,
+    /// 发给指定 client id
+    Client(String),
+    /// 发给指定房间内除发送者外的所有成员
+    Room(String),
+}
+
+/// 客户端 -> 服务器的入站消息信封
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InboundMessage {
+    pub destination: Destination,
+    pub payload: serde_json::Value,
+}
+
+/// 服务器 -> 客户端的出站消息信封
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboundMessage {
+    /// 消息来源的 client id；由主机（前端）发出的消息没有来源 id
+    pub from: Option<String>,
+    pub payload: serde_json::Value,
+}
+
+/// 客户端 -> 服务器的顶层帧：既可以是一条要转发的消息，也可以是房间成员管理指令。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Message(InboundMessage),
+    JoinRoom { room: String },
+    LeaveRoom { room: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destination_round_trips_through_json() {
+        for dest in [
+            Destination::Broadcast,
+            Destination::Client("alice".to_string()),
+            Destination::Room("lobby".to_string()),
+        ] {
+            let encoded = serde_json::to_string(&dest).unwrap();
+            let decoded: Destination = serde_json::from_str(&encoded).unwrap();
+            assert_eq!(serde_json::to_string(&decoded).unwrap(), encoded);
+        }
+    }
+
+    #[test]
+    fn client_frame_message_round_trips() {
+        let frame = ClientFrame::Message(InboundMessage {
+            destination: Destination::Room("lobby".to_string()),
+            payload: serde_json::json!({"text": "hello"}),
+        });
+
+        let encoded = serde_json::to_string(&frame).unwrap();
+        let decoded: ClientFrame = serde_json::from_str(&encoded).unwrap();
+        match decoded {
+            ClientFrame::Message(inbound) => {
+                assert_eq!(inbound.destination, Destination::Room("lobby".to_string()));
+                assert_eq!(inbound.payload, serde_json::json!({"text": "hello"}));
+            }
+            other => panic!("expected ClientFrame::Message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn client_frame_join_and_leave_room_round_trip() {
+        let join = ClientFrame::JoinRoom { room: "lobby".to_string() };
+        let leave = ClientFrame::LeaveRoom { room: "lobby".to_string() };
+
+        let join_decoded: ClientFrame =
+            serde_json::from_str(&serde_json::to_string(&join).unwrap()).unwrap();
+        let leave_decoded: ClientFrame =
+            serde_json::from_str(&serde_json::to_string(&leave).unwrap()).unwrap();
+
+        assert!(matches!(join_decoded, ClientFrame::JoinRoom { room } if room == "lobby"));
+        assert!(matches!(leave_decoded, ClientFrame::LeaveRoom { room } if room == "lobby"));
+    }
+
+    #[test]
+    fn client_frame_kind_tag_uses_snake_case() {
+        let frame = ClientFrame::JoinRoom { room: "lobby".to_string() };
+        let encoded = serde_json::to_string(&frame).unwrap();
+        assert!(encoded.contains("\"kind\":\"join_room\""));
+    }
+}